@@ -0,0 +1,70 @@
+use super::*;
+use std::cell::RefCell;
+
+// lol-html's core crate isn't vendored into this repo, so there's no
+// `Element::ancestors()` to read from. Instead the ancestor stack is
+// maintained entirely on the c-api side: `ancestor_tracking_handlers`
+// (wired up last in `HtmlRewriterBuilder::get_safe_handlers`, after every
+// sanitizer/user selector, so it never sees an element as its own ancestor)
+// pushes each element's tag name on its start tag and pops it via
+// `on_end_tag`. Void elements have no end tag to hook, so `on_end_tag`
+// returns an error for them and nothing is pushed, which is correct since
+// they can't have descendants anyway.
+//
+// Push/pop isn't guaranteed to balance out on its own: a handler can bail
+// out mid-document with `Err`, or an end tag can be removed so its pop never
+// runs. `HtmlRewriterBuilder::get_safe_handlers` calls `reset_ancestors` at
+// the start of every rewrite to clear out anything a previous, possibly
+// aborted, rewrite left behind. This is thread-local state scoped to "the
+// current rewrite on this thread" — it is not safe to drive two rewriters
+// concurrently on the same thread, as they would corrupt each other's stack.
+thread_local! {
+    static ANCESTORS: RefCell<Vec<String>> = RefCell::new(Vec::new());
+}
+
+pub(crate) fn reset_ancestors() {
+    ANCESTORS.with(|a| a.borrow_mut().clear());
+}
+
+pub(crate) fn push_ancestor(tag_name: String) {
+    ANCESTORS.with(|a| a.borrow_mut().push(tag_name));
+}
+
+pub(crate) fn pop_ancestor() {
+    ANCESTORS.with(|a| {
+        a.borrow_mut().pop();
+    });
+}
+
+/// Number of elements enclosing the element currently being handled.
+#[no_mangle]
+pub extern "C" fn cool_thing_element_ancestor_count() -> size_t {
+    ANCESTORS.with(|a| a.borrow().len())
+}
+
+/// Returns a pointer to the UTF8 tag name of the ancestor at `index` (0 is
+/// the closest enclosing element), writing its length to `name_len_out`.
+/// Returns NULL and writes 0 if `index` is out of bounds. The returned
+/// pointer is valid only for the duration of the current handler call and
+/// must not be freed by the caller.
+#[no_mangle]
+pub extern "C" fn cool_thing_element_ancestor_name_at(
+    index: size_t,
+    name_len_out: *mut size_t,
+) -> *const c_char {
+    ANCESTORS.with(|a| {
+        let ancestors = a.borrow();
+
+        // The stack is pushed outermost-first, so count from its top.
+        match ancestors.iter().rev().nth(index) {
+            Some(name) => {
+                unsafe { *name_len_out = name.len() };
+                name.as_ptr() as *const c_char
+            }
+            None => {
+                unsafe { *name_len_out = 0 };
+                std::ptr::null()
+            }
+        }
+    })
+}
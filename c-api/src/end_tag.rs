@@ -0,0 +1,90 @@
+use super::*;
+use std::slice;
+
+/// Mirrors `lol_html::html_content::ContentType`, letting C callers choose
+/// whether content inserted around an end tag is HTML-escaped or passed
+/// through raw.
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub enum ContentType {
+    Text,
+    Html,
+}
+
+impl From<ContentType> for lol_html::html_content::ContentType {
+    fn from(content_type: ContentType) -> Self {
+        match content_type {
+            ContentType::Text => lol_html::html_content::ContentType::Text,
+            ContentType::Html => lol_html::html_content::ContentType::Html,
+        }
+    }
+}
+
+// `EndTag::name()` returns an owned `String`, so unlike e.g.
+// `cool_thing_text_chunk_content_get` (which borrows from the chunk) there's
+// no buffer on the Rust side for a returned pointer to alias. The name is
+// therefore handed over as a caller-owned buffer that must be released with
+// `cool_thing_end_tag_name_free`.
+#[no_mangle]
+pub extern "C" fn cool_thing_end_tag_name_get(
+    end_tag: *mut EndTag,
+    name_len_out: *mut size_t,
+) -> *mut c_char {
+    let name = to_ref!(end_tag).name().into_bytes().into_boxed_slice();
+
+    unsafe { *name_len_out = name.len() };
+
+    Box::into_raw(name) as *mut c_char
+}
+
+/// Releases a buffer previously returned by `cool_thing_end_tag_name_get`.
+#[no_mangle]
+pub extern "C" fn cool_thing_end_tag_name_free(name: *mut c_char, name_len: size_t) {
+    drop(unsafe { Box::from_raw(slice::from_raw_parts_mut(name as *mut u8, name_len)) });
+}
+
+#[no_mangle]
+pub extern "C" fn cool_thing_end_tag_name_set(
+    end_tag: *mut EndTag,
+    name: *const c_char,
+    name_len: size_t,
+) -> c_int {
+    let name = unwrap_or_ret_err_code! { to_str!(name, name_len) };
+
+    to_ref_mut!(end_tag).set_name(name.into());
+
+    0
+}
+
+#[no_mangle]
+pub extern "C" fn cool_thing_end_tag_before(
+    end_tag: *mut EndTag,
+    content: *const c_char,
+    content_len: size_t,
+    content_type: ContentType,
+) -> c_int {
+    let content = unwrap_or_ret_err_code! { to_str!(content, content_len) };
+
+    to_ref_mut!(end_tag).before(content, content_type.into());
+
+    0
+}
+
+#[no_mangle]
+pub extern "C" fn cool_thing_end_tag_after(
+    end_tag: *mut EndTag,
+    content: *const c_char,
+    content_len: size_t,
+    content_type: ContentType,
+) -> c_int {
+    let content = unwrap_or_ret_err_code! { to_str!(content, content_len) };
+
+    to_ref_mut!(end_tag).after(content, content_type.into());
+
+    0
+}
+
+#[no_mangle]
+pub extern "C" fn cool_thing_end_tag_remove(end_tag: *mut EndTag) {
+    to_ref_mut!(end_tag).remove();
+}
@@ -1,5 +1,10 @@
 use super::*;
+use crate::sanitizer::SanitizerConfig;
 use libc::c_void;
+use std::cell::RefCell;
+use std::error::Error;
+use std::fmt;
+use std::mem;
 
 macro_rules! wrap_handler {
     ($handler:ident, $user_data:expr) => {{
@@ -14,10 +19,97 @@ macro_rules! wrap_handler {
         // of structure field.
         let user_data = $user_data;
 
-        move |arg: &mut _| unsafe { $handler(arg, user_data) }
+        move |arg: &mut _| {
+            // Clear out any message a previous, unrelated handler call set
+            // but never consumed (because it returned 0), so it can't get
+            // mistakenly attached to *this* call's error instead.
+            LAST_HANDLER_ERROR.with(|e| *e.borrow_mut() = None);
+
+            // A nonzero return means the C callback wants to stop rewriting;
+            // it may have called `cool_thing_handler_set_error` first to
+            // attach a message, otherwise a generic error is reported.
+            match unsafe { $handler(arg, user_data) } {
+                0 => Ok(()),
+                _ => Err(take_last_handler_error()),
+            }
+        }
     }};
 }
 
+thread_local! {
+    static LAST_HANDLER_ERROR: RefCell<Option<String>> = RefCell::new(None);
+}
+
+#[derive(Debug)]
+struct HandlerError(String);
+
+impl fmt::Display for HandlerError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl Error for HandlerError {}
+
+fn take_last_handler_error() -> Box<dyn Error + Send + Sync> {
+    let message = LAST_HANDLER_ERROR
+        .with(|e| e.borrow_mut().take())
+        .unwrap_or_else(|| "content handler returned an error".to_owned());
+
+    Box::new(HandlerError(message))
+}
+
+/// Lets a C content handler attach a message to the error that will be
+/// propagated out of `rewriter.write`/`rewriter.end` when it returns a
+/// nonzero status. Calling this is optional; handlers that don't care about
+/// the message can just return nonzero.
+#[no_mangle]
+pub extern "C" fn cool_thing_handler_set_error(message: *const c_char, message_len: size_t) {
+    if let Ok(message) = to_str!(message, message_len) {
+        LAST_HANDLER_ERROR.with(|e| *e.borrow_mut() = Some(message.to_owned()));
+    }
+}
+
+// lol-html delivers text in arbitrarily-sized chunks, so a handler that
+// wants to see a whole text node at once (e.g. to do a regex replace that
+// might span a chunk boundary) needs the intermediate chunks buffered and
+// suppressed until the node's last chunk arrives. The accumulated text is
+// then handed to the C handler through `cool_thing_text_chunk_content_get`
+// (via `text_chunk::with_coalesced_content`), not by pre-replacing the
+// chunk, so the handler's own choice of content type still governs what's
+// written if it calls `cool_thing_text_chunk_replace`. If it doesn't, the
+// coalesced text is written back verbatim as `Html` so the node round-trips
+// unchanged instead of silently losing the chunks that were suppressed.
+fn coalesced_text_handler(
+    handler: unsafe extern "C" fn(*mut TextChunk, *mut c_void) -> c_int,
+    user_data: *mut c_void,
+) -> impl FnMut(&mut TextChunk) -> HandlerResult {
+    let mut buffer = String::new();
+
+    move |chunk: &mut TextChunk| {
+        buffer.push_str(chunk.as_str());
+
+        if !chunk.last_in_text_node() {
+            chunk.remove();
+            return Ok(());
+        }
+
+        let full_text = mem::take(&mut buffer);
+
+        let (result, replaced) = crate::text_chunk::with_coalesced_content(&full_text, || {
+            wrap_handler!(handler, user_data)(chunk)
+        });
+
+        result?;
+
+        if !replaced {
+            chunk.replace(&full_text, lol_html::html_content::ContentType::Html);
+        }
+
+        Ok(())
+    }
+}
+
 struct ExternHandler<T> {
     handler: Option<T>,
     user_data: *mut c_void,
@@ -30,9 +122,10 @@ impl<T> ExternHandler<T> {
 }
 
 pub struct ExternDocumentContentHandlers {
-    doctype: ExternHandler<unsafe extern "C" fn(*mut Doctype, *mut c_void)>,
-    comments: ExternHandler<unsafe extern "C" fn(*mut Comment, *mut c_void)>,
-    text: ExternHandler<unsafe extern "C" fn(*mut TextChunk, *mut c_void)>,
+    doctype: ExternHandler<unsafe extern "C" fn(*mut Doctype, *mut c_void) -> c_int>,
+    comments: ExternHandler<unsafe extern "C" fn(*mut Comment, *mut c_void) -> c_int>,
+    text: ExternHandler<unsafe extern "C" fn(*mut TextChunk, *mut c_void) -> c_int>,
+    coalesce_text: bool,
 }
 
 impl ExternDocumentContentHandlers {
@@ -48,7 +141,11 @@ impl ExternDocumentContentHandlers {
         }
 
         if let Some(handler) = self.text.handler {
-            handlers = handlers.text(wrap_handler!(handler, self.text.user_data));
+            if self.coalesce_text {
+                handlers = handlers.text(coalesced_text_handler(handler, self.text.user_data));
+            } else {
+                handlers = handlers.text(wrap_handler!(handler, self.text.user_data));
+            }
         }
 
         handlers
@@ -56,17 +153,41 @@ impl ExternDocumentContentHandlers {
 }
 
 pub struct ExternElementContentHandlers {
-    element: ExternHandler<unsafe extern "C" fn(*mut Element, *mut c_void)>,
-    comments: ExternHandler<unsafe extern "C" fn(*mut Comment, *mut c_void)>,
-    text: ExternHandler<unsafe extern "C" fn(*mut TextChunk, *mut c_void)>,
+    element: ExternHandler<unsafe extern "C" fn(*mut Element, *mut c_void) -> c_int>,
+    end_tag: ExternHandler<unsafe extern "C" fn(*mut EndTag, *mut c_void) -> c_int>,
+    comments: ExternHandler<unsafe extern "C" fn(*mut Comment, *mut c_void) -> c_int>,
+    text: ExternHandler<unsafe extern "C" fn(*mut TextChunk, *mut c_void) -> c_int>,
+    coalesce_text: bool,
 }
 
 impl ExternElementContentHandlers {
     pub fn as_safe_element_content_handlers(&self) -> ElementContentHandlers {
         let mut handlers = ElementContentHandlers::default();
 
-        if let Some(handler) = self.element.handler {
-            handlers = handlers.element(wrap_handler!(handler, self.element.user_data));
+        let element_handler = self.element.handler;
+        let element_user_data = self.element.user_data;
+        let end_tag_handler = self.end_tag.handler;
+        let end_tag_user_data = self.end_tag.user_data;
+
+        // The core has no separate "end tag" selector; an end tag handler
+        // only becomes reachable by registering it on the matching element
+        // as that element's start tag is seen, so both handlers are wired
+        // up through the same `element` closure.
+        if element_handler.is_some() || end_tag_handler.is_some() {
+            handlers = handlers.element(move |el: &mut Element| {
+                if let Some(handler) = element_handler {
+                    wrap_handler!(handler, element_user_data)(el)?;
+                }
+
+                if let Some(handler) = end_tag_handler {
+                    // Void/self-closing elements have no end tag to hook, so
+                    // `on_end_tag` fails for them; that's a no-op, not an
+                    // error to propagate (mirrors `ancestor_tracking_handlers`).
+                    let _ = el.on_end_tag(wrap_handler!(handler, end_tag_user_data));
+                }
+
+                Ok(())
+            });
         }
 
         if let Some(handler) = self.comments.handler {
@@ -74,7 +195,11 @@ impl ExternElementContentHandlers {
         }
 
         if let Some(handler) = self.text.handler {
-            handlers = handlers.text(wrap_handler!(handler, self.text.user_data));
+            if self.coalesce_text {
+                handlers = handlers.text(coalesced_text_handler(handler, self.text.user_data));
+            } else {
+                handlers = handlers.text(wrap_handler!(handler, self.text.user_data));
+            }
         }
 
         handlers
@@ -86,29 +211,92 @@ pub struct SafeContentHandlers<'b> {
     pub element: Vec<(&'b Selector, ElementContentHandlers<'b>)>,
 }
 
-#[derive(Default)]
 pub struct HtmlRewriterBuilder {
     document_content_handlers: Vec<ExternDocumentContentHandlers>,
     element_content_handlers: Vec<(Selector, ExternElementContentHandlers)>,
+    sanitizer: Option<SanitizerConfig>,
+    sanitizer_selector: Option<Selector>,
+    ancestor_tracking_selector: Selector,
+}
+
+impl Default for HtmlRewriterBuilder {
+    fn default() -> Self {
+        HtmlRewriterBuilder {
+            document_content_handlers: Vec::new(),
+            element_content_handlers: Vec::new(),
+            sanitizer: None,
+            sanitizer_selector: None,
+            ancestor_tracking_selector: "*".parse().expect("\"*\" is always a valid selector"),
+        }
+    }
 }
 
 impl HtmlRewriterBuilder {
     pub fn get_safe_handlers(&self) -> SafeContentHandlers {
-        SafeContentHandlers {
-            document: self
-                .document_content_handlers
+        // Guard against stale entries from a previous rewrite on this thread
+        // (e.g. one that ended early because a handler returned `Err`, or
+        // whose last open element had its end tag removed, leaving the
+        // matching pop never run): the ancestor stack is only meaningful for
+        // a single rewrite, so it starts clean here rather than trusting
+        // push/pop to always balance out.
+        crate::element::reset_ancestors();
+
+        // The sanitizer, if any, is inserted ahead of every user-registered
+        // handler so user callbacks only ever see already-vetted nodes.
+        let mut document: Vec<_> = self
+            .sanitizer
+            .iter()
+            .map(|s| s.document_content_handlers())
+            .collect();
+
+        document.extend(
+            self.document_content_handlers
                 .iter()
-                .map(|h| h.as_safe_document_content_handlers())
-                .collect(),
-            element: self
-                .element_content_handlers
+                .map(|h| h.as_safe_document_content_handlers()),
+        );
+
+        let mut element: Vec<_> = self
+            .sanitizer
+            .iter()
+            .zip(self.sanitizer_selector.iter())
+            .map(|(s, selector)| (selector, s.element_content_handlers()))
+            .collect();
+
+        element.extend(
+            self.element_content_handlers
                 .iter()
-                .map(|(s, h)| (s, h.as_safe_element_content_handlers()))
-                .collect(),
-        }
+                .map(|(s, h)| (s, h.as_safe_element_content_handlers())),
+        );
+
+        // Registered last so it runs after every sanitizer/user handler for
+        // the same element's start tag: by the time it pushes the tag name,
+        // no handler for this element itself has been able to observe it as
+        // its own ancestor.
+        element.push((
+            &self.ancestor_tracking_selector,
+            ancestor_tracking_handlers(),
+        ));
+
+        SafeContentHandlers { document, element }
     }
 }
 
+fn ancestor_tracking_handlers() -> ElementContentHandlers<'static> {
+    ElementContentHandlers::default().element(|el: &mut Element| {
+        let tag_name = el.tag_name();
+        let pushed = el.on_end_tag(|_end_tag| {
+            crate::element::pop_ancestor();
+            Ok(())
+        });
+
+        if pushed.is_ok() {
+            crate::element::push_ancestor(tag_name);
+        }
+
+        Ok(())
+    })
+}
+
 #[no_mangle]
 pub extern "C" fn cool_thing_rewriter_builder_new() -> *mut HtmlRewriterBuilder {
     to_ptr_mut(HtmlRewriterBuilder::default())
@@ -117,12 +305,13 @@ pub extern "C" fn cool_thing_rewriter_builder_new() -> *mut HtmlRewriterBuilder
 #[no_mangle]
 pub extern "C" fn cool_thing_rewriter_builder_add_document_content_handlers(
     builder: *mut HtmlRewriterBuilder,
-    doctype_handler: Option<unsafe extern "C" fn(*mut Doctype, *mut c_void)>,
+    doctype_handler: Option<unsafe extern "C" fn(*mut Doctype, *mut c_void) -> c_int>,
     doctype_handler_user_data: *mut c_void,
-    comments_handler: Option<unsafe extern "C" fn(*mut Comment, *mut c_void)>,
+    comments_handler: Option<unsafe extern "C" fn(*mut Comment, *mut c_void) -> c_int>,
     comments_handler_user_data: *mut c_void,
-    text_handler: Option<unsafe extern "C" fn(*mut TextChunk, *mut c_void)>,
+    text_handler: Option<unsafe extern "C" fn(*mut TextChunk, *mut c_void) -> c_int>,
     text_handler_user_data: *mut c_void,
+    coalesce_text_chunks: c_int,
 ) {
     let builder = to_ref_mut!(builder);
 
@@ -130,6 +319,7 @@ pub extern "C" fn cool_thing_rewriter_builder_add_document_content_handlers(
         doctype: ExternHandler::new(doctype_handler, doctype_handler_user_data),
         comments: ExternHandler::new(comments_handler, comments_handler_user_data),
         text: ExternHandler::new(text_handler, text_handler_user_data),
+        coalesce_text: coalesce_text_chunks != 0,
     };
 
     builder.document_content_handlers.push(handlers);
@@ -140,12 +330,15 @@ pub extern "C" fn cool_thing_rewriter_builder_add_element_content_handlers(
     builder: *mut HtmlRewriterBuilder,
     selector: *const c_char,
     selector_len: size_t,
-    element_handler: Option<unsafe extern "C" fn(*mut Element, *mut c_void)>,
+    element_handler: Option<unsafe extern "C" fn(*mut Element, *mut c_void) -> c_int>,
     element_handler_user_data: *mut c_void,
-    comments_handler: Option<unsafe extern "C" fn(*mut Comment, *mut c_void)>,
+    end_tag_handler: Option<unsafe extern "C" fn(*mut EndTag, *mut c_void) -> c_int>,
+    end_tag_handler_user_data: *mut c_void,
+    comments_handler: Option<unsafe extern "C" fn(*mut Comment, *mut c_void) -> c_int>,
     comments_handler_user_data: *mut c_void,
-    text_handler: Option<unsafe extern "C" fn(*mut TextChunk, *mut c_void)>,
+    text_handler: Option<unsafe extern "C" fn(*mut TextChunk, *mut c_void) -> c_int>,
     text_handler_user_data: *mut c_void,
+    coalesce_text_chunks: c_int,
 ) -> c_int {
     let selector = unwrap_or_ret_err_code! { to_str!(selector, selector_len) };
     let selector = unwrap_or_ret_err_code! { selector.parse::<Selector>() };
@@ -153,8 +346,10 @@ pub extern "C" fn cool_thing_rewriter_builder_add_element_content_handlers(
 
     let handlers = ExternElementContentHandlers {
         element: ExternHandler::new(element_handler, element_handler_user_data),
+        end_tag: ExternHandler::new(end_tag_handler, end_tag_handler_user_data),
         comments: ExternHandler::new(comments_handler, comments_handler_user_data),
         text: ExternHandler::new(text_handler, text_handler_user_data),
+        coalesce_text: coalesce_text_chunks != 0,
     };
 
     builder.element_content_handlers.push((selector, handlers));
@@ -162,6 +357,24 @@ pub extern "C" fn cool_thing_rewriter_builder_add_element_content_handlers(
     0
 }
 
+#[no_mangle]
+// Takes ownership of `sanitizer`: the builder frees it once it's dropped, so
+// the caller must not call `cool_thing_sanitizer_free` on a config that was
+// passed here, and must not reuse the pointer afterwards.
+pub extern "C" fn cool_thing_rewriter_builder_add_sanitizer(
+    builder: *mut HtmlRewriterBuilder,
+    sanitizer: *mut SanitizerConfig,
+) -> c_int {
+    let sanitizer = *to_box!(sanitizer);
+    let selector = unwrap_or_ret_err_code! { "*".parse::<Selector>() };
+    let builder = to_ref_mut!(builder);
+
+    builder.sanitizer = Some(sanitizer);
+    builder.sanitizer_selector = Some(selector);
+
+    0
+}
+
 #[no_mangle]
 pub extern "C" fn cool_thing_rewriter_builder_free(builder: *mut HtmlRewriterBuilder) {
     drop(to_box!(builder));
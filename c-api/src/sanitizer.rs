@@ -0,0 +1,179 @@
+use super::*;
+use std::collections::{HashMap, HashSet};
+
+/// Allowlist-based sanitizer configuration, built up from the C side via
+/// `cool_thing_sanitizer_allow_*` calls and then attached to a
+/// `HtmlRewriterBuilder` so its handlers run before any user-registered ones.
+#[derive(Default)]
+pub struct SanitizerConfig {
+    allowed_elements: HashSet<String>,
+    allowed_attributes: HashMap<String, HashSet<String>>,
+    allowed_protocols: HashMap<(String, String), HashSet<String>>,
+    strip_disallowed: bool,
+}
+
+impl SanitizerConfig {
+    fn element_allowed(&self, tag_name: &str) -> bool {
+        self.allowed_elements.contains(tag_name)
+    }
+
+    fn attribute_allowed(&self, tag_name: &str, attr_name: &str) -> bool {
+        self.allowed_attributes
+            .get(tag_name)
+            .map_or(false, |attrs| attrs.contains(attr_name))
+    }
+
+    // `href`/`src`-style attributes are only rejected if a protocol allowlist
+    // was registered for this (tag, attribute) pair; unregistered pairs are
+    // left to the plain attribute allowlist above. The scheme is the text up
+    // to the first ':', matched ASCII-case-insensitively, with no trimming or
+    // normalization of surrounding whitespace/control characters, so consumers that want to
+    // treat e.g. a leading tab or newline before "javascript:" as that scheme
+    // need to register it themselves, or reject the attribute outright via
+    // the plain attribute allowlist. Since this is an allowlist, an
+    // unrecognized or oddly-formatted scheme is simply rejected, so this
+    // fails safe either way.
+    fn protocol_allowed(&self, tag_name: &str, attr_name: &str, value: &str) -> bool {
+        let key = (tag_name.to_owned(), attr_name.to_owned());
+
+        match self.allowed_protocols.get(&key) {
+            None => true,
+            Some(schemes) => match value.split_once(':') {
+                Some((scheme, _)) => schemes.contains(&scheme.to_ascii_lowercase()),
+                None => true,
+            },
+        }
+    }
+
+    fn sanitize_element(&self, element: &mut Element) -> HandlerResult {
+        let tag_name = element.tag_name();
+
+        if !self.element_allowed(&tag_name) {
+            if self.strip_disallowed {
+                element.remove();
+            } else {
+                element.remove_and_keep_content();
+            }
+
+            return Ok(());
+        }
+
+        let attrs_to_remove: Vec<String> = element
+            .attributes()
+            .iter()
+            .map(|attr| attr.name())
+            .filter(|name| {
+                !self.attribute_allowed(&tag_name, name)
+                    || element
+                        .get_attribute(name)
+                        .map_or(false, |value| !self.protocol_allowed(&tag_name, name, &value))
+            })
+            .collect();
+
+        for name in attrs_to_remove {
+            element.remove_attribute(&name);
+        }
+
+        Ok(())
+    }
+
+    // Comments and the doctype carry no element/attribute identity for an
+    // allowlist to judge, so they're always stripped rather than gated on
+    // `strip_disallowed` (which only chooses how *disallowed elements* are
+    // torn down) — otherwise the default config would pass untrusted
+    // comments and the doctype straight through unsanitized.
+    pub fn document_content_handlers(&self) -> DocumentContentHandlers {
+        DocumentContentHandlers::default()
+            .doctype(|doctype: &mut Doctype| {
+                doctype.remove();
+
+                Ok(())
+            })
+            .comments(|comment: &mut Comment| {
+                comment.remove();
+
+                Ok(())
+            })
+    }
+
+    pub fn element_content_handlers(&self) -> ElementContentHandlers {
+        ElementContentHandlers::default().element(move |el: &mut Element| self.sanitize_element(el))
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn cool_thing_sanitizer_new() -> *mut SanitizerConfig {
+    to_ptr_mut(SanitizerConfig::default())
+}
+
+#[no_mangle]
+pub extern "C" fn cool_thing_sanitizer_allow_element(
+    sanitizer: *mut SanitizerConfig,
+    tag_name: *const c_char,
+    tag_name_len: size_t,
+) -> c_int {
+    let tag_name = unwrap_or_ret_err_code! { to_str!(tag_name, tag_name_len) };
+    let sanitizer = to_ref_mut!(sanitizer);
+
+    sanitizer.allowed_elements.insert(tag_name.to_owned());
+
+    0
+}
+
+#[no_mangle]
+pub extern "C" fn cool_thing_sanitizer_allow_attribute(
+    sanitizer: *mut SanitizerConfig,
+    tag_name: *const c_char,
+    tag_name_len: size_t,
+    attr_name: *const c_char,
+    attr_name_len: size_t,
+) -> c_int {
+    let tag_name = unwrap_or_ret_err_code! { to_str!(tag_name, tag_name_len) };
+    let attr_name = unwrap_or_ret_err_code! { to_str!(attr_name, attr_name_len) };
+    let sanitizer = to_ref_mut!(sanitizer);
+
+    sanitizer
+        .allowed_attributes
+        .entry(tag_name.to_owned())
+        .or_insert_with(HashSet::new)
+        .insert(attr_name.to_owned());
+
+    0
+}
+
+#[no_mangle]
+pub extern "C" fn cool_thing_sanitizer_allow_protocol(
+    sanitizer: *mut SanitizerConfig,
+    tag_name: *const c_char,
+    tag_name_len: size_t,
+    attr_name: *const c_char,
+    attr_name_len: size_t,
+    scheme: *const c_char,
+    scheme_len: size_t,
+) -> c_int {
+    let tag_name = unwrap_or_ret_err_code! { to_str!(tag_name, tag_name_len) };
+    let attr_name = unwrap_or_ret_err_code! { to_str!(attr_name, attr_name_len) };
+    let scheme = unwrap_or_ret_err_code! { to_str!(scheme, scheme_len) };
+    let sanitizer = to_ref_mut!(sanitizer);
+
+    sanitizer
+        .allowed_protocols
+        .entry((tag_name.to_owned(), attr_name.to_owned()))
+        .or_insert_with(HashSet::new)
+        .insert(scheme.to_ascii_lowercase());
+
+    0
+}
+
+#[no_mangle]
+pub extern "C" fn cool_thing_sanitizer_set_strip_disallowed(
+    sanitizer: *mut SanitizerConfig,
+    strip_disallowed: c_int,
+) {
+    to_ref_mut!(sanitizer).strip_disallowed = strip_disallowed != 0;
+}
+
+#[no_mangle]
+pub extern "C" fn cool_thing_sanitizer_free(sanitizer: *mut SanitizerConfig) {
+    drop(to_box!(sanitizer));
+}
@@ -0,0 +1,74 @@
+use super::*;
+use crate::end_tag::ContentType;
+use std::cell::{Cell, RefCell};
+
+// While a coalesced text handler (see `coalesced_text_handler` in
+// rewriter_builder.rs) is running on the text node's last chunk, the
+// content getter below reports the whole node's accumulated text instead of
+// that one chunk's own text. `COALESCED_REPLACED` records whether the
+// handler actually called `cool_thing_text_chunk_replace` while this was in
+// effect, so the caller can fall back to writing the coalesced text back out
+// verbatim when it didn't.
+thread_local! {
+    static COALESCED_CONTENT: RefCell<Option<String>> = RefCell::new(None);
+    static COALESCED_REPLACED: Cell<bool> = Cell::new(false);
+}
+
+pub(crate) fn with_coalesced_content<R>(content: &str, f: impl FnOnce() -> R) -> (R, bool) {
+    COALESCED_CONTENT.with(|c| *c.borrow_mut() = Some(content.to_owned()));
+    COALESCED_REPLACED.with(|r| r.set(false));
+
+    let result = f();
+    let replaced = COALESCED_REPLACED.with(Cell::get);
+
+    COALESCED_CONTENT.with(|c| *c.borrow_mut() = None);
+
+    (result, replaced)
+}
+
+#[no_mangle]
+pub extern "C" fn cool_thing_text_chunk_content_get(
+    chunk: *mut TextChunk,
+    content_len_out: *mut size_t,
+) -> *const c_char {
+    let coalesced = COALESCED_CONTENT.with(|c| {
+        c.borrow().as_ref().map(|content| {
+            let bytes = content.as_bytes();
+            (bytes.as_ptr(), bytes.len())
+        })
+    });
+
+    let (ptr, len) = coalesced.unwrap_or_else(|| {
+        let content = to_ref!(chunk).as_str().as_bytes();
+        (content.as_ptr(), content.len())
+    });
+
+    unsafe { *content_len_out = len };
+
+    ptr as *const c_char
+}
+
+#[no_mangle]
+pub extern "C" fn cool_thing_text_chunk_is_last_in_text_node(chunk: *mut TextChunk) -> c_int {
+    to_ref!(chunk).last_in_text_node() as c_int
+}
+
+/// Replaces the chunk's content, choosing via `content_type` whether it is
+/// HTML-escaped (`Text`) or inserted as-is (`Html`). Call this from a
+/// coalesced text handler (see
+/// `cool_thing_rewriter_builder_add_element_content_handlers`) to do a
+/// whole-node replacement instead of operating on individual chunks.
+#[no_mangle]
+pub extern "C" fn cool_thing_text_chunk_replace(
+    chunk: *mut TextChunk,
+    content: *const c_char,
+    content_len: size_t,
+    content_type: ContentType,
+) -> c_int {
+    let content = unwrap_or_ret_err_code! { to_str!(content, content_len) };
+
+    COALESCED_REPLACED.with(|r| r.set(true));
+    to_ref_mut!(chunk).replace(content, content_type.into());
+
+    0
+}